@@ -0,0 +1,179 @@
+//! Local control socket used to inspect and poke the running daemon out of band from its
+//! `--interval` sleep: `status`, `resolve-now` and `reload`.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// What the daemon currently believes about a single hostname peer's endpoint.
+#[derive(Debug, Clone, Default)]
+pub struct PeerStatus {
+    pub config_endpoint: String,
+    pub resolved_endpoint: Option<SocketAddr>,
+    pub last_change: Option<SystemTime>,
+    pub last_error: Option<String>,
+}
+
+/// Status table shared between the main loop, which updates it as peers are resolved, and the
+/// control socket, which serves it in response to `status`. Keyed by `"<interface>/<public_key>"`.
+pub type StatusTable = Arc<Mutex<HashMap<String, PeerStatus>>>;
+
+/// Requests made via the control socket that the main loop acts on between interval ticks.
+pub enum ControlEvent {
+    /// Run an immediate pass of the resolve/sync loop, out of band from `--interval`.
+    ResolveNow,
+    /// Re-scan `wireguard_directory`/`networkd_directory` and rebuild the interface list.
+    Reload,
+}
+
+/// Bind `socket_path` and serve control requests for the lifetime of the process.
+///
+/// Accept errors are logged and do not bring down the listener; a stale socket file left behind
+/// by an unclean shutdown is removed before binding.
+pub fn listen(socket_path: &str, status: StatusTable, events: Sender<ControlEvent>) -> std::io::Result<()> {
+    let path = Path::new(socket_path);
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    // The daemon typically runs as root to manage WireGuard; without this, the socket is left at
+    // whatever the process umask dictates, letting any local user read peer status or trigger
+    // resolve-now/reload. Restrict it to the owner.
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+
+    log::info!("Control socket listening on {socket_path}");
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let status = status.clone();
+                    let events = events.clone();
+                    std::thread::spawn(move || handle_client(stream, &status, &events));
+                }
+                Err(err) => log::warn!("Control socket accept error: {err}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Record that `key` was just resolved to `resolved_endpoint` (or failed, in which case pass
+/// `None` and set `error` instead). Only bumps `last_change` when the endpoint actually changed.
+pub fn record_resolved(status: &StatusTable, key: String, config_endpoint: String, resolved_endpoint: Option<SocketAddr>, error: Option<String>) {
+    let mut table = status.lock().unwrap();
+    let entry = table.entry(key).or_default();
+
+    entry.config_endpoint = config_endpoint;
+    if resolved_endpoint.is_some() && entry.resolved_endpoint != resolved_endpoint {
+        entry.last_change = Some(SystemTime::now());
+    }
+    if resolved_endpoint.is_some() {
+        entry.resolved_endpoint = resolved_endpoint;
+    }
+    entry.last_error = error;
+}
+
+fn handle_client(stream: UnixStream, status: &StatusTable, events: &Sender<ControlEvent>) {
+    let mut reader = BufReader::new(match stream.try_clone() {
+        Ok(s) => s,
+        Err(err) => {
+            log::warn!("Unable to clone control socket connection: {err}");
+            return;
+        }
+    });
+    let mut writer = stream;
+
+    let mut line = String::new();
+    if reader.read_line(&mut line).is_err() {
+        return;
+    }
+
+    let response = match line.trim() {
+        "status" => status_json(status),
+        "resolve-now" => {
+            let _ = events.send(ControlEvent::ResolveNow);
+            "ok".to_string()
+        }
+        "reload" => {
+            let _ = events.send(ControlEvent::Reload);
+            "ok".to_string()
+        }
+        other => format!("error: unknown command '{other}', expected status|resolve-now|reload"),
+    };
+
+    let _ = writeln!(writer, "{response}");
+}
+
+fn status_json(status: &StatusTable) -> String {
+    let table = status.lock().unwrap();
+
+    let entries: Vec<String> = table
+        .iter()
+        .map(|(key, peer)| {
+            format!(
+                "{}:{{\"config_endpoint\":{},\"resolved_endpoint\":{},\"last_change\":{},\"last_error\":{}}}",
+                json_str(key),
+                json_str(&peer.config_endpoint),
+                json_opt(peer.resolved_endpoint.map(|a| a.to_string())),
+                json_opt(peer.last_change.and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok()).map(|d| d.as_secs().to_string())),
+                json_opt(peer.last_error.clone()),
+            )
+        })
+        .collect();
+
+    format!("{{{}}}", entries.join(","))
+}
+
+/// Config endpoints and public keys come from file content an operator controls, not a trusted
+/// schema, so every string field must be escaped before it lands in the JSON output.
+fn json_str(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn json_opt(value: Option<String>) -> String {
+    match value {
+        Some(v) => json_str(&v),
+        None => "null".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_str_escapes_quotes_and_backslashes() {
+        assert_eq!(json_str(r#"weird"key\with\stuff"#), r#""weird\"key\\with\\stuff""#);
+    }
+
+    #[test]
+    fn json_opt_passes_through_none() {
+        assert_eq!(json_opt(None), "null");
+    }
+
+    #[test]
+    fn status_json_escapes_config_endpoint_and_key() {
+        let status: StatusTable = Arc::new(Mutex::new(HashMap::new()));
+        status.lock().unwrap().insert(
+            r#"wg0/weird"key"#.to_string(),
+            PeerStatus {
+                config_endpoint: r#"evil\host:51820"#.to_string(),
+                ..Default::default()
+            },
+        );
+
+        let json = status_json(&status);
+        assert!(json.contains(r#""wg0/weird\"key""#));
+        assert!(json.contains(r#""evil\\host:51820""#));
+    }
+}
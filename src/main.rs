@@ -1,15 +1,23 @@
+mod control;
 mod networkd;
+mod watch;
 mod wireguard_api;
 mod wireguard_config;
 
-use std::{error::Error, process::ExitCode, thread::sleep, time::Duration};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::{error::Error, process::ExitCode, time::Duration};
 
 //use anyhow::{Context, Result};
 use clap::Parser;
 use log::LevelFilter;
+use notify::RecommendedWatcher;
 use simple_logger::SimpleLogger;
 
+use control::ControlEvent;
 use wireguard_api::{Client, UpdateError};
+use wireguard_config::AddressFamily;
 
 // The main config
 #[derive(Debug, Parser)]
@@ -31,6 +39,30 @@ pub struct Args {
     #[clap(long, env, default_value("5m"), value_parser = humantime::parse_duration)]
     interval: Duration,
 
+    /// Only re-resolve a peer's endpoint once its last handshake is older than this, with units
+    /// 'ms', 's', 'm', 'h', e.g. 2m15s. Peers which never completed a handshake are always
+    /// resolved. Defaults to WireGuard's rekey timeout (120s) plus some keepalive slack.
+    #[clap(long, env, default_value("135s"), value_parser = humantime::parse_duration)]
+    rehandshake_threshold: Duration,
+
+    /// Reconcile the full wireguard config (AllowedIPs, PresharedKey, PersistentKeepalive) with
+    /// the live interface instead of only resolving and updating peer endpoints.
+    #[clap(long, env)]
+    sync: bool,
+
+    /// When used together with --sync, also remove peers from the live interface that are no
+    /// longer present in the config file.
+    #[clap(long, env, requires = "sync")]
+    remove_extra_peers: bool,
+
+    /// Path of the control socket accepting `status`/`resolve-now`/`reload` commands
+    #[clap(long, env, default_value("/run/wg-reresolve-dns.sock"))]
+    control_socket: String,
+
+    /// Which address family to prefer when a hostname resolves to both IPv4 and IPv6
+    #[clap(long, env, default_value("auto"), value_enum)]
+    address_family: AddressFamily,
+
     /// Enable verbose output
     #[clap(short, long, env)]
     verbose: bool,
@@ -68,9 +100,10 @@ fn main() -> ExitCode {
     ExitCode::FAILURE
 }
 
-fn run_loop(cfg: &Args) -> Result<(), Box<dyn Error>> {
-    let mut wg = Client::connect()?;
-
+/// Scan `wireguard_directory`/`networkd_directory` and build the list of `(interface, config_file)`
+/// pairs to check. Re-run on a `reload` control request to pick up interfaces or netdev mappings
+/// that changed since the daemon started.
+fn build_interface_list(cfg: &Args) -> Vec<(String, String)> {
     // If any networkd devices are used, build a map of networkd devices to wireguard devices
     let networkd_devices = if cfg.wireguard_interfaces.iter().any(|iface| iface.ends_with("-netdev")) {
         Some(networkd::get_networkd_devices(&cfg.networkd_directory))
@@ -78,8 +111,7 @@ fn run_loop(cfg: &Args) -> Result<(), Box<dyn Error>> {
         None
     };
 
-    let interface_list: Vec<_> = cfg
-        .wireguard_interfaces
+    cfg.wireguard_interfaces
         .iter()
         .filter_map(|iface| {
             if let Some(iface) = iface.strip_suffix("-netdev") {
@@ -89,18 +121,48 @@ fn run_loop(cfg: &Args) -> Result<(), Box<dyn Error>> {
                     return None;
                 };
 
-                Some((iface, device_config_file.clone()))
+                Some((iface.to_string(), device_config_file.clone()))
             } else {
-                Some((iface, format!("{}{iface}.conf", cfg.wireguard_directory)))
+                Some((iface.to_string(), format!("{}{iface}.conf", cfg.wireguard_directory)))
             }
         })
-        .collect();
+        .collect()
+}
+
+fn run_loop(cfg: &Args) -> Result<(), Box<dyn Error>> {
+    let mut wg = Client::connect()?;
+
+    let status: control::StatusTable = Arc::new(Mutex::new(HashMap::new()));
+    let (control_tx, control_rx) = mpsc::channel();
+
+    if let Err(err) = control::listen(&cfg.control_socket, status.clone(), control_tx.clone()) {
+        log::warn!("Unable to start control socket on {}: {err}", cfg.control_socket);
+    }
+
+    let mut interface_list = build_interface_list(cfg);
+
+    // Kept alive for the lifetime of the loop: dropping it would stop the filesystem watch.
+    // Falls back to pure --interval polling if watching isn't available (e.g. inotify limits).
+    // Rebuilt on every `reload`, since `interface_list` (and therefore the set of extra files to
+    // watch) may have changed.
+    let mut watcher = build_watcher(cfg, &interface_list, control_tx.clone());
 
     loop {
         log::info!("Checking endpoints");
 
         for (interface, file) in &interface_list {
-            let res = wg.update_endpoints(interface, file);
+            let res = if cfg.sync {
+                wg.sync_config(
+                    interface,
+                    file,
+                    cfg.remove_extra_peers,
+                    cfg.address_family,
+                    cfg.rehandshake_threshold,
+                    &status,
+                )
+            } else {
+                wg.update_endpoints(interface, file, cfg.rehandshake_threshold, cfg.address_family, &status)
+            };
 
             match res {
                 Err(
@@ -119,6 +181,45 @@ fn run_loop(cfg: &Args) -> Result<(), Box<dyn Error>> {
             }
         }
 
-        sleep(cfg.interval);
+        wait_for_next_run(cfg, &control_rx, &mut interface_list, &mut watcher, &control_tx);
     }
 }
+
+/// Sleep for `--interval`, unless the control socket requests a `resolve-now` (returns early) or
+/// a `reload` (returns early after rebuilding `interface_list` and re-creating `watcher` so it
+/// picks up any extra files the new `interface_list` needs watched).
+fn wait_for_next_run(
+    cfg: &Args,
+    control_rx: &Receiver<ControlEvent>,
+    interface_list: &mut Vec<(String, String)>,
+    watcher: &mut Option<RecommendedWatcher>,
+    control_tx: &mpsc::Sender<ControlEvent>,
+) {
+    match control_rx.recv_timeout(cfg.interval) {
+        Ok(ControlEvent::ResolveNow) => log::info!("Control socket requested an immediate re-resolve"),
+        Ok(ControlEvent::Reload) => {
+            log::info!("Control socket requested a config reload");
+            *interface_list = build_interface_list(cfg);
+            *watcher = build_watcher(cfg, interface_list, control_tx.clone());
+        }
+        Err(mpsc::RecvTimeoutError::Timeout | mpsc::RecvTimeoutError::Disconnected) => {}
+    }
+}
+
+/// Build the filesystem watcher for `wireguard_directory`/`networkd_directory` plus the extra
+/// files of `interface_list`. Returns `None` (falling back to pure `--interval` polling) if
+/// watching isn't available, e.g. inotify limits.
+fn build_watcher(
+    cfg: &Args,
+    interface_list: &[(String, String)],
+    control_tx: mpsc::Sender<ControlEvent>,
+) -> Option<RecommendedWatcher> {
+    watch::watch(
+        &cfg.wireguard_directory,
+        &cfg.networkd_directory,
+        &interface_list.iter().map(|(_, file)| file.clone()).collect::<Vec<_>>(),
+        control_tx,
+    )
+    .inspect_err(|err| log::warn!("Unable to watch config directories for changes, falling back to polling only: {err}"))
+    .ok()
+}
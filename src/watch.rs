@@ -0,0 +1,58 @@
+//! Filesystem watch subsystem: turns config edits into an immediate [`ControlEvent`] instead of
+//! waiting for the next `--interval` tick. Shares its event channel with [`crate::control`], so
+//! the main loop doesn't need to distinguish where a wake-up request came from.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::control::ControlEvent;
+
+/// Watch `wireguard_directory` and `networkd_directory`, plus `extra_files` (the resolved
+/// `.conf`/`.netdev` paths of the current `interface_list`, in case any of them live outside
+/// those directories), for changes. On any create/modify/remove, send [`ControlEvent::Reload`]
+/// so the main loop re-scans the directories and rebuilds `interface_list` - a cheap, safe
+/// superset of "just re-resolve", since the endpoint check runs again right after anyway.
+///
+/// Returns the watcher, which must be kept alive for as long as watching should continue;
+/// dropping it tears down the underlying inotify instance.
+pub fn watch(
+    wireguard_directory: &str,
+    networkd_directory: &str,
+    extra_files: &[String],
+    events: Sender<ControlEvent>,
+) -> notify::Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+        Ok(event) if event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove() => {
+            log::debug!("Config change detected, triggering reload: {event:?}");
+            let _ = events.send(ControlEvent::Reload);
+        }
+        Ok(_) => {}
+        Err(err) => log::warn!("Config watch error: {err}"),
+    })?;
+
+    // Watch each path independently: networkd_directory in particular is only relevant when a
+    // -netdev interface is configured and may well not exist otherwise, and one missing/watchable
+    // path shouldn't take down watching for the others.
+    watch_path(&mut watcher, Path::new(wireguard_directory));
+    watch_path(&mut watcher, Path::new(networkd_directory));
+
+    for file in extra_files {
+        let path = PathBuf::from(file);
+        if let Some(parent) = path.parent()
+            && parent != Path::new(wireguard_directory)
+            && parent != Path::new(networkd_directory)
+        {
+            watch_path(&mut watcher, &path);
+        }
+    }
+
+    Ok(watcher)
+}
+
+fn watch_path(watcher: &mut RecommendedWatcher, path: &Path) {
+    if let Err(err) = watcher.watch(path, RecursiveMode::NonRecursive) {
+        log::warn!("Unable to watch '{}' for changes: {err}", path.display());
+    }
+}
@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::fmt::{self, Display};
+use std::time::{Duration, SystemTime};
 
 #[derive(Debug)]
 #[allow(dead_code)]
@@ -10,6 +11,9 @@ pub enum UpdateError {
     ErrorSettingDevice(String),
 }
 
+/// The all-zero preshared key, which wireguard-tools/the netlink API treat as "no preshared key".
+const NO_PRESHARED_KEY: [u8; 32] = [0u8; 32];
+
 pub struct Client(wireguard_uapi::WgSocket);
 
 impl Client {
@@ -18,7 +22,14 @@ impl Client {
         Ok(Client(wg))
     }
 
-    pub fn update_endpoints(&mut self, interface_name: &str, config_file: &str) -> Result<(), UpdateError> {
+    pub fn update_endpoints(
+        &mut self,
+        interface_name: &str,
+        config_file: &str,
+        rehandshake_threshold: Duration,
+        address_family: crate::wireguard_config::AddressFamily,
+        status: &crate::control::StatusTable,
+    ) -> Result<(), UpdateError> {
         use wireguard_uapi::DeviceInterface;
         use wireguard_uapi::linux::set::{Device, Peer, WgPeerF};
 
@@ -41,7 +52,7 @@ impl Client {
         let peers = get_cfg_peers(config_file)
             .map_err(|e| UpdateError::ConfigFileError(format!("Unable to read config file: {e}")))?
             .into_iter()
-            .filter(|peer| matches!(peer.endpoint, Endpoint::Hostname { .. }));
+            .filter(|peer| matches!(peer.endpoint, Some(Endpoint::Hostname { .. })));
 
         let mut peer_updates: Vec<([u8; 32], std::net::SocketAddr)> = vec![];
 
@@ -51,14 +62,39 @@ impl Client {
 
             // Find matching peer in active interface
             if let Some(active_peer) = device.peers.iter().find(|&p| p.public_key == raw_public_key) {
+                // Skip peers whose handshake is still fresh, no need to needlessly re-resolve them.
+                // A peer that never completed a handshake is always resolved.
+                if active_peer.last_handshake_time != SystemTime::UNIX_EPOCH {
+                    match active_peer.last_handshake_time.elapsed() {
+                        Ok(since_handshake) if since_handshake < rehandshake_threshold => {
+                            log::debug!(
+                                "Skipping peer {}, last handshake {since_handshake:?} ago",
+                                &peer.public_key
+                            );
+                            continue;
+                        }
+                        _ => {}
+                    }
+                }
+
                 // Resolve the endpoint address
-                match peer.endpoint.resolve() {
+                // The filter above guarantees this peer has a hostname endpoint.
+                let endpoint = peer.endpoint.as_ref().unwrap();
+                let status_key = format!("{interface_name}/{}", &peer.public_key);
+
+                match endpoint.resolve(address_family) {
                     Err(err) => {
-                        log::warn!("Unable to resolve endpoint '{}': {err}", &peer.endpoint);
+                        log::warn!("Unable to resolve endpoint '{endpoint}': {err}");
+                        crate::control::record_resolved(status, status_key, endpoint.to_string(), None, Some(err.to_string()));
                     }
-                    Ok(new_endpoint) => {
+                    Ok(candidates) => {
+                        // Prefer whichever candidate matches the currently active endpoint's
+                        // family, to avoid gratuitous churn between IPv4/IPv6 on every check.
+                        let new_endpoint = pick_endpoint(&candidates, active_peer.endpoint);
+                        crate::control::record_resolved(status, status_key, endpoint.to_string(), Some(new_endpoint), None);
+
                         // Check if the endpoint address has changed
-                        if active_peer.endpoint.unwrap() == new_endpoint {
+                        if active_peer.endpoint == Some(new_endpoint) {
                             log::info!("Endpoint for peer {} not changed", &peer.public_key);
                             continue;
                         }
@@ -92,6 +128,228 @@ impl Client {
             .map_err(|e| UpdateError::ErrorSettingDevice(format!("{e:#}")))?;
         Ok(())
     }
+
+    /// Reconcile the full `[Peer]` configuration (`AllowedIPs`, `PresharedKey`,
+    /// `PersistentKeepalive`, in addition to `Endpoint`) against the live interface, adding and
+    /// updating peers as needed. If `remove_extra_peers` is set, peers present on the live
+    /// interface but absent from `config_file` are removed as well.
+    pub fn sync_config(
+        &mut self,
+        interface_name: &str,
+        config_file: &str,
+        remove_extra_peers: bool,
+        address_family: crate::wireguard_config::AddressFamily,
+        rehandshake_threshold: Duration,
+        status: &crate::control::StatusTable,
+    ) -> Result<(), UpdateError> {
+        use wireguard_uapi::DeviceInterface;
+        use wireguard_uapi::linux::set::{AllowedIp, Device, Peer, WgPeerF};
+
+        use crate::wireguard_config::{Endpoint, get_cfg_peers};
+
+        let device = match self.0.get_device(DeviceInterface::from_name(interface_name)) {
+            Err(err) => {
+                return Err(UpdateError::MissingWireguardInterface(format!(
+                    "Unable to get wireguard interface {interface_name}: {err}"
+                )));
+            }
+            Ok(dev) => dev,
+        };
+
+        let cfg_peers = get_cfg_peers(config_file)
+            .map_err(|e| UpdateError::ConfigFileError(format!("Unable to read config file: {e}")))?;
+
+        let mut device_update = Device::from_ifname(interface_name);
+
+        // Keep the owned conversions (resolved endpoint, decoded keys/IPs) alive for as long as
+        // device_update borrows from them.
+        let mut raw_public_keys = Vec::with_capacity(cfg_peers.len());
+        let mut resolved_endpoints = Vec::with_capacity(cfg_peers.len());
+        let mut preshared_keys = Vec::with_capacity(cfg_peers.len());
+        let mut allowed_ip_lists = Vec::with_capacity(cfg_peers.len());
+
+        for peer in &cfg_peers {
+            let raw_public_key = peer.get_raw_public_key().map_err(UpdateError::InvalidPublicKey)?;
+
+            let active_peer = device.peers.iter().find(|p| p.public_key == raw_public_key);
+            let active_endpoint = active_peer.and_then(|p| p.endpoint);
+
+            // Same handshake-freshness gate as update_endpoints: skip the needless DNS lookup for
+            // a hostname peer that already looks alive, and keep using its current endpoint.
+            let stale_or_unseen = match active_peer {
+                Some(p) if p.last_handshake_time != SystemTime::UNIX_EPOCH => {
+                    !matches!(p.last_handshake_time.elapsed(), Ok(since) if since < rehandshake_threshold)
+                }
+                _ => true,
+            };
+
+            let status_key = format!("{interface_name}/{}", &peer.public_key);
+
+            resolved_endpoints.push(match &peer.endpoint {
+                Some(Endpoint::Hostname { .. }) if !stale_or_unseen => active_endpoint,
+                Some(endpoint) => match endpoint.resolve(address_family) {
+                    Ok(candidates) => {
+                        let addr = pick_endpoint(&candidates, active_endpoint);
+                        crate::control::record_resolved(status, status_key, endpoint.to_string(), Some(addr), None);
+                        Some(addr)
+                    }
+                    Err(err) => {
+                        log::warn!("Unable to resolve endpoint '{endpoint}': {err}");
+                        crate::control::record_resolved(status, status_key, endpoint.to_string(), None, Some(err.to_string()));
+                        None
+                    }
+                },
+                None => None,
+            });
+
+            raw_public_keys.push(raw_public_key);
+
+            // A missing PresharedKey/PersistentKeepalive in the config means "none configured",
+            // which on the wire is the all-zero key / interval 0 - resolve that here so the diff
+            // below can compare like-for-like against the live peer's always-populated fields.
+            preshared_keys.push(match &peer.preshared_key {
+                Some(key) => decode_preshared_key(key).map_err(UpdateError::InvalidPublicKey)?,
+                None => NO_PRESHARED_KEY,
+            });
+
+            let mut allowed_ips = peer
+                .allowed_ips
+                .iter()
+                .map(|ip| parse_allowed_ip(ip))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| UpdateError::ConfigFileError(format!("Invalid AllowedIPs for peer {}: {e}", peer.public_key)))?;
+            allowed_ips.sort();
+            allowed_ip_lists.push(allowed_ips);
+        }
+
+        // Only include a peer in the update if it's new or at least one field actually differs
+        // from the live device - an unconditional push here would needlessly rewrite every peer's
+        // netlink state on every --sync tick, the same churn chunk0-1 eliminated at the DNS layer.
+        for (i, peer) in cfg_peers.iter().enumerate() {
+            let raw_public_key = &raw_public_keys[i];
+            let active_peer = device.peers.iter().find(|p| &p.public_key == raw_public_key);
+
+            let desired_keepalive = peer.persistent_keepalive.unwrap_or(0);
+
+            let mut peer_update = Peer::from_public_key(raw_public_key);
+
+            let changed = match active_peer {
+                None => {
+                    // New peer: nothing to diff against, send every field.
+                    peer_update.endpoint = resolved_endpoints[i].as_ref();
+                    peer_update.preshared_key = Some(&preshared_keys[i]);
+                    peer_update.persistent_keepalive_interval = Some(desired_keepalive);
+                    peer_update.flags.push(WgPeerF::ReplaceAllowedIps);
+                    for (ipaddr, cidr_mask) in &allowed_ip_lists[i] {
+                        peer_update.allowed_ips.push(AllowedIp {
+                            ipaddr,
+                            cidr_mask: Some(*cidr_mask),
+                        });
+                    }
+                    true
+                }
+                Some(active) => {
+                    peer_update.flags.push(WgPeerF::UpdateOnly);
+                    let mut changed = false;
+
+                    if let Some(endpoint) = &resolved_endpoints[i]
+                        && active.endpoint != Some(*endpoint)
+                    {
+                        peer_update.endpoint = Some(endpoint);
+                        changed = true;
+                    }
+
+                    if active.preshared_key != preshared_keys[i] {
+                        peer_update.preshared_key = Some(&preshared_keys[i]);
+                        changed = true;
+                    }
+
+                    if active.persistent_keepalive_interval != desired_keepalive {
+                        peer_update.persistent_keepalive_interval = Some(desired_keepalive);
+                        changed = true;
+                    }
+
+                    let mut active_allowed_ips: Vec<_> =
+                        active.allowed_ips.iter().map(|ip| (ip.ipaddr, ip.cidr_mask)).collect();
+                    active_allowed_ips.sort();
+
+                    if active_allowed_ips != allowed_ip_lists[i] {
+                        peer_update.flags.push(WgPeerF::ReplaceAllowedIps);
+                        for (ipaddr, cidr_mask) in &allowed_ip_lists[i] {
+                            peer_update.allowed_ips.push(AllowedIp {
+                                ipaddr,
+                                cidr_mask: Some(*cidr_mask),
+                            });
+                        }
+                        changed = true;
+                    }
+
+                    changed
+                }
+            };
+
+            if changed {
+                device_update.peers.push(peer_update);
+            }
+        }
+
+        if remove_extra_peers {
+            for active_peer in &device.peers {
+                if !raw_public_keys.contains(&active_peer.public_key) {
+                    let mut removal = Peer::from_public_key(&active_peer.public_key);
+                    removal.flags.push(WgPeerF::RemoveMe);
+                    device_update.peers.push(removal);
+                }
+            }
+        }
+
+        if device_update.peers.is_empty() {
+            return Ok(());
+        }
+
+        self.0
+            .set_device(device_update)
+            .map_err(|e| UpdateError::ErrorSettingDevice(format!("{e:#}")))?;
+        Ok(())
+    }
+}
+
+/// Pick the resolved candidate to use: prefer whichever matches `current`'s address family (to
+/// avoid gratuitous IPv4/IPv6 churn), falling back to the first (deterministically ordered)
+/// candidate of the requested family.
+fn pick_endpoint(candidates: &[std::net::SocketAddr], current: Option<std::net::SocketAddr>) -> std::net::SocketAddr {
+    current
+        .and_then(|current| candidates.iter().find(|addr| addr.is_ipv6() == current.is_ipv6()).copied())
+        .unwrap_or(candidates[0])
+}
+
+/// Parse a `PresharedKey` entry (base64, like `PublicKey`) into its raw bytes.
+fn decode_preshared_key(key: &str) -> Result<[u8; 32], String> {
+    use base64::{Engine as _, engine::general_purpose};
+
+    let vec = general_purpose::STANDARD
+        .decode(key)
+        .map_err(|err| format!("Unable to parse preshared key: {err}"))?;
+
+    if vec.len() != 32 {
+        return Err(format!("Preshared key has {} bytes, expected 32", vec.len()));
+    }
+
+    let mut raw = [0u8; 32];
+    raw.copy_from_slice(&vec);
+    Ok(raw)
+}
+
+/// Parse an `AllowedIPs` entry (e.g. `"10.0.0.0/24"`) into an address and CIDR mask.
+fn parse_allowed_ip(entry: &str) -> Result<(std::net::IpAddr, u8), String> {
+    let (addr, mask) = entry
+        .split_once('/')
+        .ok_or_else(|| format!("Missing CIDR mask in '{entry}'"))?;
+
+    Ok((
+        addr.parse().map_err(|err| format!("Invalid address '{addr}': {err}"))?,
+        mask.parse().map_err(|err| format!("Invalid CIDR mask '{mask}': {err}"))?,
+    ))
 }
 
 impl Display for UpdateError {
@@ -101,3 +359,66 @@ impl Display for UpdateError {
 }
 
 impl Error for UpdateError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> std::net::SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn pick_endpoint_prefers_current_family() {
+        let candidates = [addr("10.0.0.1:51820"), addr("[fd00::1]:51820")];
+
+        assert_eq!(pick_endpoint(&candidates, Some(addr("192.168.1.1:51820"))), candidates[0]);
+        assert_eq!(pick_endpoint(&candidates, Some(addr("[fd00::2]:51820"))), candidates[1]);
+    }
+
+    #[test]
+    fn pick_endpoint_falls_back_to_first_candidate() {
+        let candidates = [addr("10.0.0.1:51820"), addr("[fd00::1]:51820")];
+
+        assert_eq!(pick_endpoint(&candidates, None), candidates[0]);
+        // No candidate matches the current endpoint's family (both IPv4 here).
+        let v4_only = [addr("10.0.0.1:51820")];
+        assert_eq!(pick_endpoint(&v4_only, Some(addr("[fd00::2]:51820"))), v4_only[0]);
+    }
+
+    #[test]
+    fn decode_preshared_key_valid() {
+        let key = decode_preshared_key("2qR0cOP3OgZwvmvdCBgy3z6ZuwedRNdxq9QlkwgsoSI=").unwrap();
+        assert_eq!(key.len(), 32);
+    }
+
+    #[test]
+    fn decode_preshared_key_wrong_length() {
+        // Valid base64, but decodes to fewer than 32 bytes.
+        assert!(decode_preshared_key("YWJj").is_err());
+    }
+
+    #[test]
+    fn decode_preshared_key_invalid_base64() {
+        assert!(decode_preshared_key("not base64!!").is_err());
+    }
+
+    #[test]
+    fn parse_allowed_ip_valid() {
+        assert_eq!(
+            parse_allowed_ip("10.0.0.2/32").unwrap(),
+            ("10.0.0.2".parse().unwrap(), 32)
+        );
+        assert_eq!(parse_allowed_ip("fd00::2/128").unwrap(), ("fd00::2".parse().unwrap(), 128));
+    }
+
+    #[test]
+    fn parse_allowed_ip_missing_mask() {
+        assert!(parse_allowed_ip("10.0.0.2").is_err());
+    }
+
+    #[test]
+    fn parse_allowed_ip_invalid_address() {
+        assert!(parse_allowed_ip("not-an-ip/32").is_err());
+    }
+}
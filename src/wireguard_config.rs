@@ -7,7 +7,11 @@ use ini::Ini;
 #[cfg_attr(test, derive(PartialEq))]
 pub struct CfgPeer {
     pub public_key: String,
-    pub endpoint: Endpoint,
+    pub endpoint: Option<Endpoint>,
+    /// Raw `AllowedIPs` entries (e.g. `"10.0.0.1/32"`), in the order they appear in the file.
+    pub allowed_ips: Vec<String>,
+    pub preshared_key: Option<String>,
+    pub persistent_keepalive: Option<u16>,
 }
 
 #[derive(Debug)]
@@ -17,18 +21,46 @@ pub enum Endpoint {
     SocketAddr(SocketAddr),
 }
 
-/// Get all peers from a wireguard config file which have a endpoint defined.
+/// Which address family to prefer when a hostname resolves to both IPv4 and IPv6 records.
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum AddressFamily {
+    /// Keep every resolved address, in a stable deterministic order.
+    #[default]
+    Auto,
+    Ipv4,
+    Ipv6,
+}
+
+impl AddressFamily {
+    fn matches(self, addr: &SocketAddr) -> bool {
+        match self {
+            AddressFamily::Auto => true,
+            AddressFamily::Ipv4 => addr.is_ipv4(),
+            AddressFamily::Ipv6 => addr.is_ipv6(),
+        }
+    }
+}
+
+/// Get all `[Peer]` sections from a wireguard config file.
+///
+/// `Endpoint` is optional, as a peer config may omit it and only become reachable once it
+/// initiates contact itself.
 pub fn get_cfg_peers(config_filepath: &str) -> Result<Vec<CfgPeer>, Box<dyn Error>> {
     let conf = Ini::load_from_file(config_filepath)?;
 
     conf.iter()
-        // Filter for all peers which have a endpoint defined.
-        .filter(|(sec, prop)| sec.unwrap_or("") == "Peer" && prop.get("Endpoint").is_some())
+        .filter(|(sec, _)| sec.unwrap_or("") == "Peer")
         // Map to a Peer struct.
         .map(|(_, prop)| {
             Ok(CfgPeer {
                 public_key: prop.get("PublicKey").ok_or("Missing PublicKey")?.to_string(),
-                endpoint: prop.get("Endpoint").unwrap().parse()?,
+                endpoint: prop.get("Endpoint").map(str::parse).transpose()?,
+                allowed_ips: prop
+                    .get("AllowedIPs")
+                    .map(|ips| ips.split(',').map(|ip| ip.trim().to_string()).collect())
+                    .unwrap_or_default(),
+                preshared_key: prop.get("PresharedKey").map(str::to_string),
+                persistent_keepalive: prop.get("PersistentKeepalive").and_then(|v| v.parse().ok()),
             })
         })
         .collect()
@@ -37,14 +69,17 @@ pub fn get_cfg_peers(config_filepath: &str) -> Result<Vec<CfgPeer>, Box<dyn Erro
 impl CfgPeer {
     /// Get the publiy key as raw slice
     pub fn get_raw_public_key(&self) -> Result<[u8; 32], String> {
-        match general_purpose::STANDARD.decode(&self.public_key) {
-            Err(err) => Err(format!("Unable to parse wireguard public key: {err}")),
-            Ok(vec) => {
-                let mut key = [0u8; 32];
-                key.copy_from_slice(&vec);
-                Ok(key)
-            }
+        let vec = general_purpose::STANDARD
+            .decode(&self.public_key)
+            .map_err(|err| format!("Unable to parse wireguard public key: {err}"))?;
+
+        if vec.len() != 32 {
+            return Err(format!("Wireguard public key has {} bytes, expected 32", vec.len()));
         }
+
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&vec);
+        Ok(key)
     }
 }
 
@@ -77,13 +112,25 @@ impl Display for Endpoint {
 }
 
 impl Endpoint {
-    pub fn resolve(&self) -> Result<SocketAddr, Box<dyn Error>> {
+    /// Resolve this endpoint to every matching candidate address, preferring `family`.
+    ///
+    /// Candidates are sorted into a stable, deterministic order so that repeated resolutions of
+    /// the same multi-record hostname don't needlessly rewrite the endpoint just because the
+    /// resolver returned records in a different order.
+    pub fn resolve(&self, family: AddressFamily) -> Result<Vec<SocketAddr>, Box<dyn Error>> {
         match self {
-            Endpoint::SocketAddr(s) => Ok(*s),
-            Endpoint::Hostname { .. } => format!("{self}")
-                .to_socket_addrs()?
-                .next()
-                .ok_or_else(|| "Unable to resolve endoint address".into()),
+            Endpoint::SocketAddr(s) => Ok(vec![*s]),
+            Endpoint::Hostname { .. } => {
+                let mut candidates: Vec<SocketAddr> =
+                    format!("{self}").to_socket_addrs()?.filter(|addr| family.matches(addr)).collect();
+
+                if candidates.is_empty() {
+                    return Err(format!("Unable to resolve endpoint address for '{self}' ({family:?})").into());
+                }
+
+                candidates.sort_by_key(|addr| (addr.is_ipv6(), addr.ip(), addr.port()));
+                Ok(candidates)
+            }
         }
     }
 }
@@ -96,7 +143,10 @@ mod tests {
         ($pub_key:expr, $endpoint:expr) => {
             CfgPeer {
                 public_key: $pub_key.to_string(),
-                endpoint: Endpoint::from_str($endpoint).unwrap(),
+                endpoint: Some(Endpoint::from_str($endpoint).unwrap()),
+                allowed_ips: vec![],
+                preshared_key: None,
+                persistent_keepalive: None,
             }
         };
     }
@@ -139,9 +189,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn full_peer_fields() {
+        let endpoints = get_cfg_peers("test-data/full_peer.conf").unwrap();
+
+        assert_eq!(
+            endpoints,
+            [CfgPeer {
+                public_key: "cMTqZVhIHKp4hfdNoU1cWdi6H+rNEzNVQ/z4isHCTmI=".to_string(),
+                endpoint: Some(Endpoint::from_str("example.com:51820").unwrap()),
+                allowed_ips: vec!["10.0.0.2/32".to_string(), "fd00::2/128".to_string()],
+                preshared_key: Some("2qR0cOP3OgZwvmvdCBgy3z6ZuwedRNdxq9QlkwgsoSI=".to_string()),
+                persistent_keepalive: Some(25),
+            }]
+        );
+    }
+
     #[test]
     fn no_endpoint() {
+        // A peer without an `Endpoint` is still returned, just with `endpoint: None`, since it
+        // may become reachable once it initiates contact itself.
         let endpoints = get_cfg_peers("test-data/no_endpoint.conf").unwrap();
-        assert_eq!(endpoints, [peer!("1213=", "example.com:51820")]);
+        assert_eq!(
+            endpoints,
+            [CfgPeer {
+                public_key: "1213=".to_string(),
+                endpoint: None,
+                allowed_ips: vec![],
+                preshared_key: None,
+                persistent_keepalive: None,
+            }]
+        );
     }
 }